@@ -0,0 +1,330 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2023 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Namespace-based sandboxed execution for build jobs.
+//!
+//! Each job is given its own mount, user, PID and UTS namespace so the
+//! build cannot see or touch anything outside of the [`Paths`] it was
+//! handed. The host filesystem is only ever reachable through the
+//! bind mounts we set up ourselves.
+
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::Path,
+    process::{Child, Command, Stdio},
+    thread::{self, JoinHandle},
+};
+
+use nix::{
+    mount::{mount, MsFlags},
+    pty::openpty,
+    sched::{unshare, CloneFlags},
+    sys::wait::{waitpid, WaitStatus},
+    unistd::{fork, pivot_root, setsid, ForkResult, Pid},
+};
+use tui::Handle;
+
+use crate::{
+    paths::{Mapping, Paths},
+    seccomp::Profile,
+};
+
+/// A running, sandboxed build job.
+///
+/// Dropping this or calling [`Executor::wait`] reaps the child process spawned as PID 1
+/// inside the namespace, and joins the thread forwarding its PTY
+/// output.
+pub struct Executor {
+    child: Child,
+    reader: Option<JoinHandle<()>>,
+}
+
+impl Executor {
+    /// Spawn `command` as PID 1 inside a fresh set of namespaces built
+    /// from `paths`, on a PTY whose styled output is forwarded to
+    /// `handle` via [`Handle::print_raw`].
+    ///
+    /// `paths.rootfs()` is assembled via [`Paths::mount_rootfs`] on the
+    /// host first - it's the overlay mountpoint, not a pre-populated
+    /// directory - then the bind mounts are created and `pivot_root`
+    /// performed from within the child's pre-exec hook, since namespace
+    /// entry and mount table manipulation must happen in the process
+    /// that will actually run inside them. `seccomp` is installed last,
+    /// right before the kernel hands control to `execve`.
+    pub fn spawn<Message: Send + 'static>(
+        paths: &Paths,
+        seccomp: Profile,
+        mut command: Command,
+        mut handle: Handle<Message>,
+    ) -> io::Result<Self> {
+        paths.mount_rootfs()?;
+
+        let mappings = sandbox_mappings(paths);
+        let rootfs_host = paths.rootfs().host;
+
+        let pty = openpty(None, None).map_err(nix_to_io)?;
+        let master = File::from(pty.master);
+        let slave = File::from(pty.slave);
+
+        command.stdin(Stdio::from(slave.try_clone()?));
+        command.stdout(Stdio::from(slave.try_clone()?));
+        command.stderr(Stdio::from(slave));
+
+        unsafe {
+            command.pre_exec(move || {
+                // Detach from boulder's controlling terminal and make
+                // the build's own PTY its controlling terminal instead,
+                // so job control and signal delivery inside the sandbox
+                // behave like a normal interactive shell would expect.
+                // Stdin is already dup'd onto the PTY slave by this point.
+                setsid().map_err(nix_to_io)?;
+                if unsafe { libc::ioctl(libc::STDIN_FILENO, libc::TIOCSCTTY as _, 0) } != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+
+                enter_namespaces().map_err(nix_to_io)?;
+
+                // `unshare(CLONE_NEWPID)` doesn't move the caller into the
+                // new PID namespace - only a process it subsequently forks
+                // does. Fork once more so the grandchild is genuinely PID 1
+                // there; the intermediate just waits for it and exits with
+                // its status, so `Executor`'s `Child` still observes a
+                // normal exit.
+                match unsafe { fork() }.map_err(nix_to_io)? {
+                    ForkResult::Parent { child } => {
+                        let code = match waitpid(child, None).map_err(nix_to_io)? {
+                            WaitStatus::Exited(_, code) => code,
+                            WaitStatus::Signaled(_, signal, _) => 128 + signal as i32,
+                            _ => 1,
+                        };
+                        std::process::exit(code);
+                    }
+                    ForkResult::Child => {
+                        bind_mappings(&rootfs_host, &mappings).map_err(nix_to_io)?;
+                        pivot_into(&rootfs_host).map_err(nix_to_io)?;
+                        mount_pseudo_filesystems().map_err(nix_to_io)?;
+                        seccomp.install()?;
+                        Ok(())
+                    }
+                }
+            });
+        }
+
+        let child = command.spawn()?;
+
+        // Drop our copy of the command; it was holding the slave-side
+        // `Stdio`s, so this closes our last reference to the slave and
+        // leaves the master as the only way to reach the PTY.
+        drop(command);
+
+        let reader = thread::spawn(move || forward_pty_output(master, &mut handle));
+
+        Ok(Self {
+            child,
+            reader: Some(reader),
+        })
+    }
+
+    /// Block until the sandboxed job exits, returning its status.
+    pub fn wait(mut self) -> io::Result<std::process::ExitStatus> {
+        self.child.wait()
+    }
+
+    pub fn pid(&self) -> Pid {
+        Pid::from_raw(self.child.id() as i32)
+    }
+}
+
+/// Read the build's PTY output until it closes, forwarding each chunk
+/// as-is so `handle`'s VT emulator sees the real escape sequences.
+fn forward_pty_output<Message>(mut master: File, handle: &mut Handle<Message>) {
+    let mut buf = [0u8; 4096];
+
+    loop {
+        match master.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => handle.print_raw(buf[..n].to_vec()),
+            // A PTY master read fails with EIO once every slave fd has
+            // been closed - that's the normal end-of-output signal, not
+            // a real error.
+            Err(_) => break,
+        }
+    }
+}
+
+impl Drop for Executor {
+    /// Reap the child so an `Executor` dropped without an explicit
+    /// `wait()` doesn't leave a zombie behind, and join the PTY reader
+    /// thread so it doesn't outlive the job.
+    fn drop(&mut self) {
+        let _ = self.child.wait();
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.join();
+        }
+    }
+}
+
+/// The set of [`Mapping`]s that must be bind mounted into the guest
+/// rootfs before `pivot_root`, paired with whether they should be
+/// writable.
+///
+/// `ccache` and `upstreams` are shared, read-write caches across jobs;
+/// `recipe` is read-only so a build can't mutate the author's sources.
+fn sandbox_mappings(paths: &Paths) -> Vec<(Mapping, bool)> {
+    vec![
+        (paths.build(), true),
+        (paths.ccache(), true),
+        (paths.upstreams(), true),
+        (paths.recipe(), false),
+        (paths.artefacts(), true),
+    ]
+}
+
+/// Unshare into new mount, user, PID and UTS namespaces, then map the
+/// invoking uid/gid to root inside the guest.
+fn enter_namespaces() -> nix::Result<()> {
+    // Read these before unsharing: inside the new user namespace they
+    // read back as the overflow id until the maps below are written.
+    let uid = nix::unistd::getuid().as_raw();
+    let gid = nix::unistd::getgid().as_raw();
+
+    unshare(
+        CloneFlags::CLONE_NEWNS
+            | CloneFlags::CLONE_NEWUSER
+            | CloneFlags::CLONE_NEWPID
+            | CloneFlags::CLONE_NEWUTS,
+    )?;
+
+    write_id_map("/proc/self/uid_map", uid)?;
+    write_file("/proc/self/setgroups", "deny")?;
+    write_id_map("/proc/self/gid_map", gid)?;
+
+    // Mount propagation must be made private before we start bind
+    // mounting, otherwise our mounts would leak back to the host.
+    mount(
+        None::<&str>,
+        "/",
+        None::<&str>,
+        MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+        None::<&str>,
+    )?;
+
+    Ok(())
+}
+
+fn write_id_map(path: &str, id: u32) -> nix::Result<()> {
+    write_file(path, &format!("0 {id} 1"))
+}
+
+fn write_file(path: &str, contents: &str) -> nix::Result<()> {
+    std::fs::write(path, contents).map_err(|_| nix::Error::EIO)
+}
+
+/// Bind mount each guest [`Mapping`] onto `rootfs_host + guest`,
+/// skipping any mapping whose host side is empty.
+fn bind_mappings(rootfs_host: &Path, mappings: &[(Mapping, bool)]) -> nix::Result<()> {
+    for (mapping, writable) in mappings {
+        if mapping.host.as_os_str().is_empty() {
+            continue;
+        }
+
+        let target = guest_path_under(rootfs_host, &mapping.guest);
+        std::fs::create_dir_all(&target).map_err(|_| nix::Error::EIO)?;
+
+        mount(
+            Some(&mapping.host),
+            &target,
+            None::<&str>,
+            MsFlags::MS_BIND | MsFlags::MS_REC,
+            None::<&str>,
+        )?;
+
+        if !writable {
+            mount(
+                None::<&Path>,
+                &target,
+                None::<&str>,
+                MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY,
+                None::<&str>,
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+fn guest_path_under(rootfs_host: &Path, guest: &Path) -> std::path::PathBuf {
+    let relative = guest.strip_prefix("/").unwrap_or(guest);
+    rootfs_host.join(relative)
+}
+
+/// `pivot_root` into the prepared rootfs while the bind mounts created
+/// above are still reachable from the old root.
+fn pivot_into(rootfs_host: &Path) -> nix::Result<()> {
+    let old_root = rootfs_host.join(".old_root");
+    std::fs::create_dir_all(&old_root).map_err(|_| nix::Error::EIO)?;
+
+    pivot_root(rootfs_host, &old_root)?;
+
+    std::env::set_current_dir("/").map_err(|_| nix::Error::EIO)?;
+
+    mount(
+        None::<&str>,
+        "/.old_root",
+        None::<&str>,
+        MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+        None::<&str>,
+    )?;
+    nix::mount::umount2("/.old_root", nix::mount::MntFlags::MNT_DETACH)?;
+    std::fs::remove_dir("/.old_root").map_err(|_| nix::Error::EIO)?;
+
+    Ok(())
+}
+
+/// Mount fresh `proc`, `sysfs`, `tmpfs /tmp` and `/dev` inside the new
+/// root, now that we're running in an isolated mount namespace.
+fn mount_pseudo_filesystems() -> nix::Result<()> {
+    std::fs::create_dir_all("/proc").map_err(|_| nix::Error::EIO)?;
+    mount(
+        Some("proc"),
+        "/proc",
+        Some("proc"),
+        MsFlags::empty(),
+        None::<&str>,
+    )?;
+
+    std::fs::create_dir_all("/sys").map_err(|_| nix::Error::EIO)?;
+    mount(
+        Some("sysfs"),
+        "/sys",
+        Some("sysfs"),
+        MsFlags::empty(),
+        None::<&str>,
+    )?;
+
+    std::fs::create_dir_all("/tmp").map_err(|_| nix::Error::EIO)?;
+    mount(
+        Some("tmpfs"),
+        "/tmp",
+        Some("tmpfs"),
+        MsFlags::empty(),
+        None::<&str>,
+    )?;
+
+    std::fs::create_dir_all("/dev").map_err(|_| nix::Error::EIO)?;
+    mount(
+        Some("/dev"),
+        "/dev",
+        None::<&str>,
+        MsFlags::MS_BIND | MsFlags::MS_REC,
+        None::<&str>,
+    )?;
+
+    Ok(())
+}
+
+fn nix_to_io(err: nix::Error) -> io::Error {
+    io::Error::from(err)
+}
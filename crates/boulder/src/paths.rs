@@ -7,10 +7,15 @@ use std::{
     path::{Path, PathBuf},
 };
 
+use nix::mount::{mount, umount2, MntFlags, MsFlags};
 use stone_recipe::Recipe;
 
 use crate::util;
 
+/// Name of the shared, read-only base image `lower` used when a job
+/// doesn't chain off a previously committed rootfs.
+pub const BASE_LOWER: &str = "base";
+
 #[derive(Debug, Clone)]
 pub struct Id(String);
 
@@ -29,6 +34,10 @@ pub struct Paths {
     host_root: PathBuf,
     guest_root: PathBuf,
     recipe_dir: PathBuf,
+    /// Name of the `lower` this job's overlay is based on - either
+    /// [`BASE_LOWER`] or a previous job's [`Paths::commit_rootfs`] result,
+    /// for chained builds.
+    lower: String,
 }
 
 impl Paths {
@@ -37,6 +46,18 @@ impl Paths {
         recipe_path: &Path,
         host_root: impl Into<PathBuf>,
         guest_root: impl Into<PathBuf>,
+    ) -> io::Result<Self> {
+        Self::with_lower(id, recipe_path, host_root, guest_root, BASE_LOWER).await
+    }
+
+    /// Like [`Paths::new`], but based on a `lower` previously produced
+    /// by [`Paths::commit_rootfs`] instead of the stock base image.
+    pub async fn with_lower(
+        id: Id,
+        recipe_path: &Path,
+        host_root: impl Into<PathBuf>,
+        guest_root: impl Into<PathBuf>,
+        lower: impl Into<String>,
     ) -> io::Result<Self> {
         let recipe_dir = recipe_path
             .parent()
@@ -48,9 +69,13 @@ impl Paths {
             host_root: host_root.into().canonicalize()?,
             guest_root: guest_root.into(),
             recipe_dir,
+            lower: lower.into(),
         };
 
         util::ensure_dir_exists(&job.rootfs().host).await?;
+        util::ensure_dir_exists(&job.lower_dir()).await?;
+        util::ensure_dir_exists(&job.upper()).await?;
+        util::ensure_dir_exists(&job.work()).await?;
         util::ensure_dir_exists(&job.artefacts().host).await?;
         util::ensure_dir_exists(&job.build().host).await?;
         util::ensure_dir_exists(&job.ccache().host).await?;
@@ -59,13 +84,87 @@ impl Paths {
         Ok(job)
     }
 
+    /// The overlay's merged mountpoint, assembled by [`Paths::mount_rootfs`].
     pub fn rootfs(&self) -> Mapping {
         Mapping {
-            host: self.host_root.join("root").join(&self.id.0),
+            host: self.host_root.join("root").join(&self.id.0).join("merged"),
             guest: "/".into(),
         }
     }
 
+    /// Shared, read-only base this job's overlay is layered on top of.
+    fn lower_dir(&self) -> PathBuf {
+        self.host_root.join("root").join("lower").join(&self.lower)
+    }
+
+    /// This job's writable overlay layer. Everything a build writes
+    /// into the rootfs lands here, including the synthetic `install`
+    /// mapping, so it survives a [`Paths::reset_rootfs`] only as long
+    /// as it hasn't been discarded.
+    fn upper(&self) -> PathBuf {
+        self.host_root.join("root").join(&self.id.0).join("upper")
+    }
+
+    /// Scratch directory overlayfs requires alongside `upper`. Must be
+    /// an empty sibling of `upper` on the same filesystem.
+    fn work(&self) -> PathBuf {
+        self.host_root.join("root").join(&self.id.0).join("work")
+    }
+
+    /// Assemble the overlay (`lower` + `upper` + `work`) at
+    /// [`Paths::rootfs`]'s mountpoint.
+    pub fn mount_rootfs(&self) -> io::Result<()> {
+        let options = format!(
+            "lowerdir={},upperdir={},workdir={}",
+            self.lower_dir().display(),
+            self.upper().display(),
+            self.work().display(),
+        );
+
+        mount(
+            Some("overlay"),
+            &self.rootfs().host,
+            Some("overlay"),
+            MsFlags::empty(),
+            Some(options.as_str()),
+        )
+        .map_err(io::Error::from)
+    }
+
+    /// Discard the `upper` layer, returning the rootfs to a pristine
+    /// base without re-extracting it. `upper` and `work` are deleted
+    /// recursively, so the cost grows with how much the build wrote.
+    pub fn reset_rootfs(&self) -> io::Result<()> {
+        // Not an error if we're called before the first mount.
+        let _ = umount2(&self.rootfs().host, MntFlags::MNT_DETACH);
+
+        for dir in [self.upper(), self.work()] {
+            if dir.exists() {
+                std::fs::remove_dir_all(&dir)?;
+            }
+            std::fs::create_dir_all(&dir)?;
+        }
+
+        self.mount_rootfs()
+    }
+
+    /// Promote this job's `upper` into a new cached `lower` named
+    /// `name`, so a subsequent chained build can start from this
+    /// build's result via [`Paths::with_lower`].
+    ///
+    /// Must be called with the overlay unmounted, since overlayfs
+    /// holds `upperdir` open for as long as it's mounted.
+    pub fn commit_rootfs(&self, name: &str) -> io::Result<PathBuf> {
+        let target = self.host_root.join("root").join("lower").join(name);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        std::fs::rename(self.upper(), &target)?;
+
+        Ok(target)
+    }
+
     pub fn artefacts(&self) -> Mapping {
         Mapping {
             host: self.host_root.join("artefacts").join(&self.id.0),
@@ -102,11 +201,17 @@ impl Paths {
     }
 
     pub fn install(&self) -> Mapping {
+        let guest = self.guest_root.join("install");
+
         Mapping {
-            // TODO: Shitty impossible state, this folder
-            // doesn't exist on host
-            host: "".into(),
-            guest: self.guest_root.join("install"),
+            // Lives in the upper overlay layer: anything written under
+            // this guest path is copied up there automatically, so it
+            // survives right up until `reset_rootfs` discards `upper`.
+            // Resolved the same way `guest_host_path` resolves a path
+            // under `rootfs()`, just rooted at `upper()` instead, since
+            // that's where the overlay actually copies writes up to.
+            host: self.upper().join(guest.strip_prefix("/").unwrap_or(&guest)),
+            guest,
         }
     }
 
@@ -0,0 +1,122 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2023 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Seccomp-BPF syscall filtering for sandboxed build jobs.
+//!
+//! The [`Profile`] is a default-deny allowlist: anything not explicitly
+//! permitted returns `EPERM` to the build instead of being serviced by
+//! the kernel. This is installed on the build process just before
+//! `execve`, after the namespace setup in [`crate::executor`].
+
+use std::io;
+
+use libseccomp::{ScmpAction, ScmpArch, ScmpFilterContext, ScmpSyscall};
+use stone_recipe::Recipe;
+
+/// Syscalls a build may need that widen beyond [`Profile::baseline`].
+///
+/// Parsed from a recipe's `seccomp.allow` list so a build that
+/// genuinely needs something we block by default (e.g. `bpf` for a
+/// kernel build) can opt in explicitly rather than us disabling the
+/// sandbox wholesale.
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    extra_allowed: Vec<String>,
+}
+
+impl Profile {
+    /// Build a profile from the recipe's declared syscall allowances.
+    pub fn from_recipe(recipe: &Recipe) -> Self {
+        Self {
+            extra_allowed: recipe.seccomp.allow.clone(),
+        }
+    }
+
+    /// The syscalls permitted unconditionally.
+    ///
+    /// Everything else is denied with `EPERM` unless listed in the
+    /// recipe's allowlist; the syscalls in [`Profile::always_denied`]
+    /// stay denied even when listed there.
+    fn baseline() -> &'static [&'static str] {
+        &[
+            "read", "write", "open", "openat", "close", "stat", "fstat", "lstat", "poll", "lseek",
+            "mmap", "mprotect", "munmap", "brk", "rt_sigaction", "rt_sigprocmask", "ioctl",
+            "pread64", "pwrite64", "readv", "writev", "access", "pipe", "select", "sched_yield",
+            "mremap", "dup", "dup2", "nanosleep", "getpid", "socket", "connect", "clone",
+            "execve", "exit", "wait4", "kill", "uname", "fcntl", "getdents64", "getcwd", "chdir",
+            "rename", "mkdir", "rmdir", "unlink", "readlink", "chmod", "chown", "umask",
+            "gettimeofday", "getrlimit", "getuid", "getgid", "setuid", "setgid", "geteuid",
+            "getegid", "sigaltstack", "statfs", "fstatfs", "arch_prctl", "exit_group",
+            "set_tid_address", "set_robust_list", "prlimit64", "getrandom", "openat2",
+        ]
+    }
+
+    /// Syscalls that would let the build escape or tamper with the
+    /// host, and are always denied regardless of the recipe's
+    /// allowlist - none of these are legitimately needed by a package
+    /// build, widenable or not.
+    fn always_denied() -> &'static [&'static str] {
+        &["mount", "umount2", "ptrace", "kexec_load", "reboot", "swapon"]
+    }
+
+    /// Compile this profile into a BPF program and install it on the
+    /// current process via `seccomp(SECCOMP_SET_MODE_FILTER, ...)`.
+    ///
+    /// Sets `PR_SET_NO_NEW_PRIVS` itself before loading the filter.
+    /// Must be called from within the sandboxed process, and must be
+    /// the last thing done before `execve`.
+    pub fn install(&self) -> io::Result<()> {
+        nix::sys::prctl::set_no_new_privs().map_err(io::Error::from)?;
+
+        let mut ctx = ScmpFilterContext::new_filter(ScmpAction::Errno(libc::EPERM))
+            .map_err(to_io_error)?;
+        ctx.add_arch(ScmpArch::Native).map_err(to_io_error)?;
+
+        for name in Self::baseline() {
+            allow(&mut ctx, name)?;
+        }
+
+        for name in &self.extra_allowed {
+            if Self::always_denied().contains(&name.as_str()) {
+                continue;
+            }
+            allow(&mut ctx, name)?;
+        }
+
+        // `clone` is allowed above for ordinary threading, but never
+        // with flags that would create a nested user namespace - that
+        // would let the build escape its own sandbox.
+        restrict_clone_flags(&mut ctx)?;
+
+        ctx.load().map_err(to_io_error)?;
+
+        Ok(())
+    }
+}
+
+fn allow(ctx: &mut ScmpFilterContext, name: &str) -> io::Result<()> {
+    let syscall = ScmpSyscall::from_name(name).map_err(to_io_error)?;
+    ctx.add_rule(ScmpAction::Allow, syscall).map_err(to_io_error)
+}
+
+fn restrict_clone_flags(ctx: &mut ScmpFilterContext) -> io::Result<()> {
+    use libseccomp::{ScmpArgCompare, ScmpCompareOp};
+
+    let clone = ScmpSyscall::from_name("clone").map_err(to_io_error)?;
+
+    ctx.add_rule_conditional(
+        ScmpAction::Errno(libc::EPERM),
+        clone,
+        &[ScmpArgCompare::new(
+            0,
+            ScmpCompareOp::MaskedEqual(libc::CLONE_NEWUSER as u64),
+            libc::CLONE_NEWUSER as u64,
+        )],
+    )
+    .map_err(to_io_error)
+}
+
+fn to_io_error(err: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, err.to_string())
+}
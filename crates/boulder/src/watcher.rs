@@ -0,0 +1,92 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2023 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Watches `upstreams` and `recipe` for changes so an edit-save-rebuild
+//! loop can re-trigger only the affected [`Stage`] instead of redoing
+//! the whole [`crate::build`] tree.
+
+use std::{io, time::Duration};
+
+use notify_debouncer_mini::{new_debouncer, notify::RecursiveMode, DebounceEventResult};
+
+use crate::paths::Paths;
+
+/// The part of the build that a detected change invalidates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// A fetched source tarball changed; re-run the affected upstream
+    /// fetch/extract step.
+    Upstreams,
+    /// The recipe directory changed; re-parse the recipe and re-run
+    /// whichever stages it describes.
+    Recipe,
+}
+
+/// Debounces filesystem churn on `upstreams`/`recipe` and calls back
+/// with the invalidated [`Stage`]. Held for as long as watching should
+/// continue; dropping it stops the watch.
+pub struct Watcher {
+    // Keeps the debouncer (and its OS watch descriptors) alive.
+    _debouncer: notify_debouncer_mini::Debouncer<notify_debouncer_mini::notify::RecommendedWatcher>,
+}
+
+impl Watcher {
+    /// Start watching, invoking `on_change` for each debounced batch of
+    /// events that falls under `upstreams` or `recipe`.
+    ///
+    /// We watch `Mapping::host` directly rather than routing through
+    /// [`Paths::guest_host_path`] - the latter resolves to a path
+    /// under the sandbox's private mount namespace, which this
+    /// host-side watcher process can never see. Churn under `ccache`
+    /// is always ignored - otherwise cache writes during a build would
+    /// re-trigger it.
+    pub fn new(paths: &Paths, mut on_change: impl FnMut(Stage) + Send + 'static) -> io::Result<Self> {
+        let upstreams_host = paths.upstreams().host;
+        let recipe_host = paths.recipe().host;
+        let ccache_host = paths.ccache().host;
+
+        let mut debouncer = new_debouncer(
+            Duration::from_millis(500),
+            move |result: DebounceEventResult| {
+                let Ok(events) = result else {
+                    return;
+                };
+
+                for event in events {
+                    if event.path.starts_with(&ccache_host) {
+                        continue;
+                    }
+
+                    let stage = if event.path.starts_with(&upstreams_host) {
+                        Stage::Upstreams
+                    } else if event.path.starts_with(&recipe_host) {
+                        Stage::Recipe
+                    } else {
+                        continue;
+                    };
+
+                    on_change(stage);
+                }
+            },
+        )
+        .map_err(notify_to_io)?;
+
+        debouncer
+            .watcher()
+            .watch(&upstreams_host, RecursiveMode::Recursive)
+            .map_err(notify_to_io)?;
+        debouncer
+            .watcher()
+            .watch(&recipe_host, RecursiveMode::Recursive)
+            .map_err(notify_to_io)?;
+
+        Ok(Self {
+            _debouncer: debouncer,
+        })
+    }
+}
+
+fn notify_to_io(err: notify_debouncer_mini::notify::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, err.to_string())
+}
@@ -0,0 +1,28 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2023 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Typed identifiers for the logical tasks (fetch, configure, compile,
+//! package, ...) that multiplex their output onto a single TUI
+//! [`run`](crate::run) invocation via [`crate::Handle`].
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+/// Identifies one job's stream of [`Event`](crate::Event)s so the
+/// render loop can keep a separate buffer, and the [`Program`](crate::Program)
+/// a separate status row, per concurrently running stage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct JobId(u64);
+
+/// Allocates increasing, unique [`JobId`]s for one [`run`](crate::run) invocation.
+#[derive(Clone, Default)]
+pub(crate) struct JobIds(Arc<AtomicU64>);
+
+impl JobIds {
+    pub(crate) fn next(&self) -> JobId {
+        JobId(self.0.fetch_add(1, Ordering::Relaxed))
+    }
+}
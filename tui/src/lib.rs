@@ -0,0 +1,41 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2023 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::ops::ControlFlow;
+
+use crossterm::event::KeyEvent;
+use ratatui::Frame;
+
+mod job;
+mod runtime;
+mod vt;
+
+pub use job::JobId;
+pub use runtime::{run, Event, Handle};
+
+/// A renderable, updatable TUI application hosted by [`run`].
+pub trait Program {
+    /// Message type fed back into [`Program::update`] via a cloned
+    /// [`Handle`].
+    type Message;
+
+    /// Number of lines the inline viewport occupies.
+    const LINES: u16;
+
+    /// Render the current state.
+    fn draw(&mut self, frame: &mut Frame);
+
+    /// Apply a message sent through a [`Handle`] scoped to `job`.
+    fn update(&mut self, job: JobId, message: Self::Message);
+
+    /// React to a key press not already claimed by [`run`](crate::run)
+    /// itself (`ctrl-c` and the built-in verbose toggle `v`). Returning
+    /// [`ControlFlow::Break`] requests that the running build be
+    /// cancelled and the program wound down; the default implementation
+    /// ignores all keys.
+    fn handle_key(&mut self, key: KeyEvent) -> ControlFlow<()> {
+        let _ = key;
+        ControlFlow::Continue(())
+    }
+}
@@ -3,10 +3,20 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use std::{
+    collections::HashMap,
     io::{stdout, Result},
+    ops::ControlFlow,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     time::Duration,
 };
 
+use crossterm::{
+    event::{Event as TermEvent, EventStream, KeyCode, KeyEventKind},
+    terminal::{disable_raw_mode, enable_raw_mode},
+};
 use futures::{stream, FutureExt, StreamExt};
 use ratatui::{
     prelude::CrosstermBackend,
@@ -17,10 +27,26 @@ use ratatui::{
 use tokio::{runtime, signal::ctrl_c, sync::mpsc, task, time};
 use tokio_stream::wrappers::IntervalStream;
 
-use crate::Program;
+use crate::{
+    job::{JobId, JobIds},
+    vt::LineEmulator,
+    Program,
+};
 
 /// Run the TUI application within the async runtime and handle all
 /// events automatically, including rendering and signals.
+///
+/// Two key bindings are built in rather than routed through
+/// [`Program::handle_key`], since they're cross-cutting runtime
+/// concerns rather than anything app-specific: `ctrl-c` requests
+/// graceful cancellation the same as a [`Program::handle_key`]
+/// returning [`ControlFlow::Break`], and `v` toggles whether raw PTY
+/// passthrough (the build tool's own stdout/stderr) is included in
+/// scrollback, off by default. Scrolling *back* through already-flushed
+/// lines isn't something this runtime manages at all - `insert_before`
+/// writes them straight into the real terminal's own scrollback, so
+/// that's left to the host terminal emulator, exactly as it would be
+/// for any other CLI tool's output.
 pub fn run<P: Program, T: Send>(
     mut program: P,
     f: impl FnOnce(Handle<P::Message>) -> T + Send + Sync + 'static,
@@ -33,7 +59,9 @@ where
         .enable_all()
         .build()?;
 
-    rt.block_on(async move {
+    enable_raw_mode()?;
+
+    let result = rt.block_on(async move {
         // Setup terminal
         let mut terminal = ratatui::Terminal::with_options(
             CrosstermBackend::new(stdout()),
@@ -49,16 +77,41 @@ where
 
         // Setup channel
         let (sender, mut receiver) = mpsc::unbounded_channel();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        // Raw PTY passthrough (the real build tool's own stdout/stderr)
+        // is almost all noise most of the time; default it off and let
+        // `v` reveal it, same as `-v`/`--verbose` on the build tools
+        // themselves.
+        let verbose = Arc::new(AtomicBool::new(false));
+        let job_ids = JobIds::default();
+
+        // Per-job raw PTY emulators (see `Event::PrintRaw`) and buffered
+        // output lines, flushed to scrollback once their job reports
+        // done. `pending_raw` is only flushed alongside `pending` while
+        // `verbose` is on - quiet by default, since it's the underlying
+        // build tool's own stdout/stderr, not our own status lines.
+        let mut vt: HashMap<JobId, LineEmulator> = HashMap::new();
+        let mut pending: HashMap<JobId, Vec<Line>> = HashMap::new();
+        let mut pending_raw: HashMap<JobId, Vec<Line>> = HashMap::new();
+        let mut cols = terminal.size()?.width;
 
         // We can receive render event or finished status
         enum Input<T> {
             Render,
             Finished(T),
             Term,
+            Key(crossterm::event::KeyEvent),
+            Resize(u16, u16),
         }
 
         // Run task
-        let mut run = task::spawn_blocking(move || f(Handle { sender }))
+        let handle = Handle {
+            job: job_ids.next(),
+            ids: job_ids,
+            sender,
+            cancelled: cancelled.clone(),
+        };
+        let mut run = task::spawn_blocking(move || f(handle))
             .map(Input::Finished)
             .into_stream();
         // Ctrl c task
@@ -66,33 +119,62 @@ where
         // Rerender @ 60fps
         let mut interval = IntervalStream::new(time::interval(Duration::from_millis(1000 / 60)))
             .map(|_| Input::Render);
+        // Keyboard/resize input
+        let mut term_events = EventStream::new().filter_map(|event| async move {
+            match event.ok()? {
+                TermEvent::Key(key) if key.kind != KeyEventKind::Release => Some(Input::Key(key)),
+                TermEvent::Resize(cols, rows) => Some(Input::Resize(cols, rows)),
+                _ => None,
+            }
+        });
 
         loop {
             // Get next input
-            let input = stream::select(&mut run, stream::select(&mut ctrl_c, &mut interval))
-                .next()
-                .await
-                .unwrap();
+            let input = stream::select(
+                &mut run,
+                stream::select(&mut ctrl_c, stream::select(&mut interval, &mut term_events)),
+            )
+            .next()
+            .await
+            .unwrap();
 
             match input {
                 Input::Render => {
-                    let mut print = vec![];
+                    let mut lines = vec![];
 
                     while let Ok(event) = receiver.try_recv() {
                         match event {
-                            Event::Message(message) => program.update(message),
-                            Event::Print(content) => print.push(content),
+                            Event::Message { job, message } => program.update(job, message),
+                            Event::Print { job, content } => {
+                                pending
+                                    .entry(job)
+                                    .or_default()
+                                    .extend(content.lines().map(|s| Line::from(s.to_string())));
+                            }
+                            Event::PrintRaw { job, content } => {
+                                let emulator =
+                                    vt.entry(job).or_insert_with(|| LineEmulator::new(cols));
+                                pending_raw
+                                    .entry(job)
+                                    .or_default()
+                                    .extend(emulator.feed(&content));
+                            }
+                            Event::JobFinished(job) => {
+                                vt.remove(&job);
+                                if let Some(buffered) = pending.remove(&job) {
+                                    lines.extend(buffered);
+                                }
+                                let raw = pending_raw.remove(&job);
+                                if verbose.load(Ordering::SeqCst) {
+                                    lines.extend(raw.into_iter().flatten());
+                                }
+                            }
                         }
                     }
 
-                    if !print.is_empty() {
-                        let lines = print
-                            .iter()
-                            .flat_map(|content| content.lines())
-                            .collect::<Vec<_>>();
+                    if !lines.is_empty() {
                         let num_lines = lines.len();
-                        let paragraph =
-                            Paragraph::new(lines.into_iter().map(Line::from).collect::<Vec<_>>());
+                        let paragraph = Paragraph::new(lines);
 
                         terminal.insert_before(num_lines as u16, |buf| {
                             paragraph.render(buf.area, buf)
@@ -109,39 +191,117 @@ where
 
                     return Ok(ret);
                 }
+                Input::Key(key) => {
+                    let is_ctrl_c = key.code == KeyCode::Char('c')
+                        && key
+                            .modifiers
+                            .contains(crossterm::event::KeyModifiers::CONTROL);
+
+                    if is_ctrl_c {
+                        cancelled.store(true, Ordering::SeqCst);
+                    } else if key.code == KeyCode::Char('v') {
+                        verbose.fetch_xor(true, Ordering::SeqCst);
+                    } else if program.handle_key(key) == ControlFlow::Break(()) {
+                        cancelled.store(true, Ordering::SeqCst);
+                    }
+                }
+                Input::Resize(new_cols, _rows) => {
+                    cols = new_cols;
+                    vt.clear();
+                }
                 Input::Term => {
-                    terminal.show_cursor()?;
-                    terminal.clear()?;
-                    std::process::exit(0);
+                    // Request the running build stop gracefully instead of
+                    // tearing the terminal down mid-build; we keep looping
+                    // until it reports back via `Input::Finished`.
+                    cancelled.store(true, Ordering::SeqCst);
                 }
             }
         }
-    })
+    });
+
+    disable_raw_mode()?;
+
+    result
 }
 
 pub struct Handle<Message> {
+    job: JobId,
+    ids: JobIds,
     sender: mpsc::UnboundedSender<Event<Message>>,
+    cancelled: Arc<AtomicBool>,
 }
 
 impl<Message> Clone for Handle<Message> {
     fn clone(&self) -> Self {
         Self {
+            job: self.job,
+            ids: self.ids.clone(),
             sender: self.sender.clone(),
+            cancelled: self.cancelled.clone(),
         }
     }
 }
 
 impl<Message> Handle<Message> {
+    /// Register a new logical task (fetch, configure, compile,
+    /// package, ...) and get back a [`Handle`] scoped to its own
+    /// [`JobId`], so its output multiplexes onto this same TUI
+    /// without mixing with other jobs.
+    pub fn job(&self) -> Handle<Message> {
+        Handle {
+            job: self.ids.next(),
+            ..self.clone()
+        }
+    }
+
+    /// The job this handle's output is tagged with.
+    pub fn id(&self) -> JobId {
+        self.job
+    }
+
     pub fn print(&mut self, content: String) {
-        let _ = self.sender.send(Event::Print(content));
+        let _ = self.sender.send(Event::Print {
+            job: self.job,
+            content,
+        });
+    }
+
+    /// Whether the user has requested graceful cancellation, either
+    /// via ctrl-c or a key handled by [`Program::handle_key`].
+    ///
+    /// Long-running build code should poll this periodically and stop
+    /// at the next safe point rather than being killed outright.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Forward raw bytes from a PTY-backed subprocess, preserving the
+    /// colors and cursor control it emits. See [`crate::vt`].
+    pub fn print_raw(&mut self, bytes: Vec<u8>) {
+        let _ = self.sender.send(Event::PrintRaw {
+            job: self.job,
+            content: bytes,
+        });
     }
 
     pub fn update(&mut self, message: Message) {
-        let _ = self.sender.send(Event::Message(message));
+        let _ = self.sender.send(Event::Message {
+            job: self.job,
+            message,
+        });
+    }
+
+    /// Mark this job done, flushing its buffered output to scrollback.
+    /// Nothing reaches [`Program::update`] for this; a program's own
+    /// per-job status rows change only through the messages it receives.
+    pub fn finish(&mut self) {
+        let _ = self.sender.send(Event::JobFinished(self.job));
     }
 }
 
 pub enum Event<Message> {
-    Message(Message),
-    Print(String),
+    Message { job: JobId, message: Message },
+    Print { job: JobId, content: String },
+    PrintRaw { job: JobId, content: Vec<u8> },
+    JobFinished(JobId),
 }
@@ -0,0 +1,180 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2023 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Translates the ANSI/VT escape sequences real build tools emit
+//! (colored `gcc`/`cmake` diagnostics, `ninja` progress bars) into
+//! styled [`ratatui`] output, instead of the plain text we'd get by
+//! stripping them.
+//!
+//! A single row [`vt100::Parser`] is used per logical line: carriage
+//! returns move the cursor back to column zero the same way a real
+//! terminal would, so a progress bar redrawing itself in place
+//! collapses to that row's final state rather than appearing once per
+//! redraw. A line is only considered finished - and handed back to the
+//! caller - once a `\n` is seen.
+
+use ratatui::{
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+};
+
+/// Emulates a single line of terminal output, accumulating bytes
+/// until a newline finishes it.
+pub struct LineEmulator {
+    parser: vt100::Parser,
+    cols: u16,
+}
+
+impl LineEmulator {
+    pub fn new(cols: u16) -> Self {
+        Self {
+            parser: vt100::Parser::new(1, cols.max(1), 0),
+            cols: cols.max(1),
+        }
+    }
+
+    /// Feed raw PTY bytes into the active line, returning each
+    /// complete, styled [`Line`] as it is finished by a `\n`.
+    ///
+    /// Any trailing bytes without a terminating newline remain
+    /// buffered in the emulator, ready to be redrawn (e.g. by a
+    /// subsequent `\r`) on the next call.
+    ///
+    /// The terminating `\n` itself is never handed to the parser: on a
+    /// single-row, zero-scrollback screen, processing a linefeed
+    /// advances/clears that row before we get a chance to read it back,
+    /// so the finished line would always come back empty. We read the
+    /// row's content back first and only then reset the parser for the
+    /// line that follows.
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<Line<'static>> {
+        let mut finished = Vec::new();
+
+        for segment in split_inclusive(bytes) {
+            let is_finished = segment.last() == Some(&b'\n');
+            let content = if is_finished {
+                &segment[..segment.len() - 1]
+            } else {
+                segment
+            };
+
+            self.parser.process(content);
+
+            if is_finished {
+                finished.push(self.current_line());
+                self.parser = vt100::Parser::new(1, self.cols, 0);
+            }
+        }
+
+        finished
+    }
+
+    /// The in-progress line as it currently stands, useful for
+    /// rendering a not-yet-finished progress redraw.
+    pub fn current_line(&self) -> Line<'static> {
+        let screen = self.parser.screen();
+        let mut spans = Vec::new();
+
+        for col in 0..self.cols {
+            let Some(cell) = screen.cell(0, col) else {
+                continue;
+            };
+
+            if cell.contents().is_empty() {
+                continue;
+            }
+
+            spans.push(Span::styled(cell.contents(), cell_style(cell)));
+        }
+
+        Line::from(spans)
+    }
+}
+
+/// Splits `bytes` into segments each ending at (and including) a
+/// newline, with a final segment holding any remainder.
+fn split_inclusive(bytes: &[u8]) -> Vec<&[u8]> {
+    let mut segments = Vec::new();
+    let mut start = 0;
+
+    for (i, b) in bytes.iter().enumerate() {
+        if *b == b'\n' {
+            segments.push(&bytes[start..=i]);
+            start = i + 1;
+        }
+    }
+
+    if start < bytes.len() {
+        segments.push(&bytes[start..]);
+    }
+
+    segments
+}
+
+fn cell_style(cell: &vt100::Cell) -> Style {
+    let mut style = Style::default();
+
+    if let Some(fg) = vt_color(cell.fgcolor()) {
+        style = style.fg(fg);
+    }
+    if let Some(bg) = vt_color(cell.bgcolor()) {
+        style = style.bg(bg);
+    }
+    if cell.bold() {
+        style = style.add_modifier(Modifier::BOLD);
+    }
+    if cell.italic() {
+        style = style.add_modifier(Modifier::ITALIC);
+    }
+    if cell.underline() {
+        style = style.add_modifier(Modifier::UNDERLINED);
+    }
+    if cell.inverse() {
+        style = style.add_modifier(Modifier::REVERSED);
+    }
+
+    style
+}
+
+fn vt_color(color: vt100::Color) -> Option<Color> {
+    match color {
+        vt100::Color::Default => None,
+        vt100::Color::Idx(idx) => Some(Color::Indexed(idx)),
+        vt100::Color::Rgb(r, g, b) => Some(Color::Rgb(r, g, b)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn feed_returns_finished_line_content() {
+        let mut emulator = LineEmulator::new(80);
+
+        let finished = emulator.feed(b"hello world\n");
+
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].to_string(), "hello world");
+    }
+
+    #[test]
+    fn feed_collapses_carriage_return_progress_redraws() {
+        let mut emulator = LineEmulator::new(80);
+
+        let finished = emulator.feed(b"progress: 50%\rprogress: 100%\n");
+
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].to_string(), "progress: 100%");
+    }
+
+    #[test]
+    fn feed_buffers_incomplete_lines() {
+        let mut emulator = LineEmulator::new(80);
+
+        let finished = emulator.feed(b"still going");
+
+        assert!(finished.is_empty());
+        assert_eq!(emulator.current_line().to_string(), "still going");
+    }
+}